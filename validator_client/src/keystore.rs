@@ -0,0 +1,216 @@
+//! Encrypted on-disk storage for validator keypairs, following the Web3 Secret Storage /
+//! EIP-2335 keystore format (the same scheme used by `ethstore`'s `disk.rs`).
+//!
+//! A keystore is a JSON document holding a cipher-text secret key, the KDF/cipher parameters
+//! needed to reconstruct the decryption key from a password, and a MAC that lets `decrypt`
+//! detect a wrong password or corrupted file before it ever touches the secret key bytes.
+use aes_ctr::cipher::stream::{NewStreamCipher, SyncStreamCipher};
+use aes_ctr::Aes128Ctr;
+use hmac::Hmac;
+use pbkdf2::pbkdf2;
+use rand::{rngs::OsRng, RngCore};
+use scrypt::{scrypt, ScryptParams};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use ssz::{Decode, Encode};
+use types::{Keypair, PublicKey, SecretKey};
+
+const DKLEN: usize = 32;
+const AES_IV_LEN: usize = 16;
+const SALT_LEN: usize = 32;
+
+/// Password-based key derivation function and its parameters, as stored in a keystore file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "function", content = "params", rename_all = "lowercase")]
+pub enum Kdf {
+    Scrypt {
+        n: u32,
+        r: u32,
+        p: u32,
+        dklen: u32,
+        salt: String,
+    },
+    Pbkdf2 {
+        c: u32,
+        dklen: u32,
+        prf: String,
+        salt: String,
+    },
+}
+
+impl Kdf {
+    /// The recommended scrypt parameters for interactive key generation (`N=2^18`).
+    pub fn scrypt_recommended() -> Self {
+        Kdf::Scrypt {
+            n: 262_144,
+            r: 8,
+            p: 1,
+            dklen: DKLEN as u32,
+            salt: hex::encode(random_bytes::<SALT_LEN>()),
+        }
+    }
+
+    /// Derive the 32-byte decryption key from `password` using these parameters.
+    fn derive_key(&self, password: &[u8]) -> Result<[u8; DKLEN], String> {
+        let mut dk = [0u8; DKLEN];
+        match self {
+            Kdf::Scrypt {
+                n, r, p, salt, ..
+            } => {
+                let salt = hex::decode(salt).map_err(|e| format!("invalid salt: {}", e))?;
+                let log2_n = (31 - n.leading_zeros()) as u8;
+                let params = ScryptParams::new(log2_n, *r, *p)
+                    .map_err(|e| format!("invalid scrypt params: {}", e))?;
+                scrypt(password, &salt, &params, &mut dk)
+                    .map_err(|e| format!("scrypt failed: {}", e))?;
+            }
+            Kdf::Pbkdf2 { c, salt, .. } => {
+                let salt = hex::decode(salt).map_err(|e| format!("invalid salt: {}", e))?;
+                pbkdf2::<Hmac<Sha256>>(password, &salt, *c, &mut dk);
+            }
+        }
+        Ok(dk)
+    }
+}
+
+/// AES-128-CTR cipher parameters, as stored in a keystore file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CipherParams {
+    pub iv: String,
+}
+
+/// The `crypto` section of an EIP-2335 keystore file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Crypto {
+    pub kdf: Kdf,
+    pub cipher: CipherParams,
+    pub cipher_text: String,
+    pub mac: String,
+}
+
+/// An EIP-2335 encrypted keystore, as serialized to disk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Keystore {
+    pub crypto: Crypto,
+    pub pubkey: String,
+}
+
+fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+fn compute_mac(derived_key: &[u8; DKLEN], cipher_text: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(&derived_key[16..32]);
+    hasher.update(cipher_text);
+    hasher.finalize().to_vec()
+}
+
+/// Encrypt arbitrary `plaintext` under `password`, using `kdf` to derive the encryption key
+/// (defaults to [`Kdf::scrypt_recommended`] when `None`).
+///
+/// This is the low-level primitive behind [`Keystore::encrypt`]; `Vault` also uses it directly
+/// to seal its master-password check without going through a `Keypair`.
+pub(crate) fn encrypt_raw(plaintext: &[u8], password: &[u8], kdf: Option<Kdf>) -> Result<Crypto, String> {
+    let kdf = kdf.unwrap_or_else(Kdf::scrypt_recommended);
+    let derived_key = kdf.derive_key(password)?;
+
+    let iv = random_bytes::<AES_IV_LEN>();
+    let mut cipher_text = plaintext.to_vec();
+    let mut cipher = Aes128Ctr::new(derived_key[0..16].into(), iv[..].into());
+    cipher.apply_keystream(&mut cipher_text);
+
+    let mac = compute_mac(&derived_key, &cipher_text);
+
+    Ok(Crypto {
+        kdf,
+        cipher: CipherParams { iv: hex::encode(iv) },
+        cipher_text: hex::encode(cipher_text),
+        mac: hex::encode(mac),
+    })
+}
+
+/// Recover the plaintext protected by `crypto`, given the correct `password`.
+///
+/// Recomputes the MAC and rejects the password before any decrypted bytes are used, so a
+/// wrong password or a corrupted file can never be mistaken for valid (if garbled) plaintext.
+pub(crate) fn decrypt_raw(crypto: &Crypto, password: &[u8]) -> Result<Vec<u8>, String> {
+    let derived_key = crypto.kdf.derive_key(password)?;
+
+    let mut cipher_text =
+        hex::decode(&crypto.cipher_text).map_err(|e| format!("invalid cipher_text: {}", e))?;
+
+    let expected_mac = compute_mac(&derived_key, &cipher_text);
+    let mac = hex::decode(&crypto.mac).map_err(|e| format!("invalid mac: {}", e))?;
+    if mac != expected_mac {
+        return Err("MAC mismatch: invalid password or corrupted keystore".into());
+    }
+
+    let iv = hex::decode(&crypto.cipher.iv).map_err(|e| format!("invalid iv: {}", e))?;
+    let mut cipher = Aes128Ctr::new(derived_key[0..16].into(), iv[..].into());
+    cipher.apply_keystream(&mut cipher_text);
+
+    Ok(cipher_text)
+}
+
+impl Keystore {
+    /// Encrypt `keypair`'s secret key under `password`, using `kdf` to derive the encryption
+    /// key (defaults to [`Kdf::scrypt_recommended`] when `None`).
+    pub fn encrypt(keypair: &Keypair, password: &[u8], kdf: Option<Kdf>) -> Result<Self, String> {
+        Ok(Keystore {
+            crypto: encrypt_raw(&keypair.sk.as_ssz_bytes(), password, kdf)?,
+            pubkey: hex::encode(keypair.pk.as_ssz_bytes()),
+        })
+    }
+
+    /// Recover the `Keypair` protected by this keystore, given the correct `password`.
+    pub fn decrypt(&self, password: &[u8]) -> Result<Keypair, String> {
+        let sk_bytes = decrypt_raw(&self.crypto, password)?;
+
+        let sk = SecretKey::from_ssz_bytes(&sk_bytes)
+            .map_err(|e| format!("Unable to decode decrypted secret key: {:?}", e))?;
+        let pk = PublicKey::from_ssz_bytes(
+            &hex::decode(&self.pubkey).map_err(|e| format!("invalid pubkey: {}", e))?,
+        )
+        .map_err(|e| format!("Unable to decode pubkey: {:?}", e))?;
+
+        Ok(Keypair { sk, pk })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scrypt_round_trip() {
+        let keypair = Keypair::random();
+        let keystore = Keystore::encrypt(&keypair, b"cats and dogs", None).expect("should encrypt");
+        let decrypted = keystore.decrypt(b"cats and dogs").expect("should decrypt");
+        assert_eq!(keypair, decrypted);
+    }
+
+    #[test]
+    fn pbkdf2_round_trip() {
+        let keypair = Keypair::random();
+        let kdf = Kdf::Pbkdf2 {
+            c: 16,
+            dklen: DKLEN as u32,
+            prf: "hmac-sha256".into(),
+            salt: hex::encode(random_bytes::<SALT_LEN>()),
+        };
+        let keystore =
+            Keystore::encrypt(&keypair, b"correct horse", Some(kdf)).expect("should encrypt");
+        let decrypted = keystore.decrypt(b"correct horse").expect("should decrypt");
+        assert_eq!(keypair, decrypted);
+    }
+
+    #[test]
+    fn wrong_password_is_rejected() {
+        let keypair = Keypair::random();
+        let keystore = Keystore::encrypt(&keypair, b"hunter2", None).expect("should encrypt");
+        assert!(keystore.decrypt(b"wrong password").is_err());
+    }
+}
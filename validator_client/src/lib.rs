@@ -0,0 +1,10 @@
+mod keystore;
+mod mnemonic;
+mod validator_directory;
+mod vault;
+
+pub use validator_directory::{
+    ValidatorDirectory, ValidatorDirectoryBuilder, ATTESTER_SLASHING_DB,
+    BLOCK_PRODUCER_SLASHING_DB,
+};
+pub use vault::Vault;
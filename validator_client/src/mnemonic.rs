@@ -0,0 +1,180 @@
+//! Deterministic HD key derivation from a BIP-39 mnemonic, analogous to the brain-wallet
+//! recovery flow in the `ethkey` CLI. A mnemonic phrase regenerates the exact same voting and
+//! withdrawal keypairs every time, so a validator directory is recoverable even if every file
+//! on disk is lost.
+//!
+//! The seed is derived from the mnemonic with PBKDF2-HMAC-SHA512 (BIP-39 §"From mnemonic to
+//! seed"), then voting/withdrawal secret keys are derived from that seed along the EIP-2334
+//! paths `m/12381/3600/{index}/0/0` and `m/12381/3600/{index}/0` using the EIP-2333 HKDF-based
+//! BLS key derivation scheme.
+use hkdf;
+use hmac::Hmac;
+use num_bigint::BigUint;
+use pbkdf2::pbkdf2;
+use sha2::{Digest, Sha256, Sha512};
+use types::{Keypair, PublicKey, SecretKey};
+
+const SEED_LEN: usize = 64;
+const MNEMONIC_PBKDF2_ROUNDS: u32 = 2048;
+const HKDF_SALT: &[u8] = b"BLS-SIG-KEYGEN-SALT-";
+
+/// The order `r` of the BLS12-381 scalar field, as a big-endian byte string.
+const CURVE_ORDER: [u8; 32] = [
+    0x73, 0xed, 0xa7, 0x53, 0x29, 0x9d, 0x7d, 0x48, 0x33, 0x39, 0xd8, 0x08, 0x09, 0xa1, 0xd8,
+    0x05, 0x53, 0xbd, 0xa4, 0x02, 0xff, 0xfe, 0x5b, 0xfe, 0xff, 0xff, 0xff, 0xff, 0x00, 0x00,
+    0x00, 0x01,
+];
+
+/// Converts a BIP-39 `mnemonic` (plus optional `passphrase`) into a 64-byte seed.
+fn mnemonic_seed(mnemonic: &str, passphrase: &str) -> [u8; SEED_LEN] {
+    let salt = format!("mnemonic{}", passphrase);
+
+    let mut seed = [0u8; SEED_LEN];
+    pbkdf2::<Hmac<Sha512>>(
+        mnemonic.as_bytes(),
+        salt.as_bytes(),
+        MNEMONIC_PBKDF2_ROUNDS,
+        &mut seed,
+    );
+    seed
+}
+
+/// `HKDF_mod_r` from EIP-2333: expand `ikm` into a BLS secret key scalar, modulo the curve order.
+fn hkdf_mod_r(ikm: &[u8]) -> [u8; 32] {
+    let curve_order = BigUint::from_bytes_be(&CURVE_ORDER);
+
+    // HKDF-Extract is run over `IKM || I2OSP(0, 1)`, per EIP-2333.
+    let mut padded_ikm = ikm.to_vec();
+    padded_ikm.push(0u8);
+
+    let mut salt = HKDF_SALT.to_vec();
+    let mut okm = [0u8; 48];
+
+    loop {
+        // EIP-2333 hashes the salt at the top of every iteration, so the very first Extract
+        // already uses `SHA256("BLS-SIG-KEYGEN-SALT-")`, not the raw label.
+        salt = Sha256::digest(&salt).to_vec();
+
+        let hk = hkdf::Hkdf::<Sha256>::new(Some(&salt), &padded_ikm);
+        hk.expand(&[0, 48u8], &mut okm)
+            .expect("48 bytes is a valid HKDF-SHA256 output length");
+
+        let candidate = BigUint::from_bytes_be(&okm) % &curve_order;
+        if candidate != BigUint::from(0u8) {
+            let mut sk = [0u8; 32];
+            let candidate_bytes = candidate.to_bytes_be();
+            sk[32 - candidate_bytes.len()..].copy_from_slice(&candidate_bytes);
+            return sk;
+        }
+
+        // Vanishingly unlikely in practice, but EIP-2333 mandates retrying (re-hashing the
+        // salt again) rather than ever deriving a zero key.
+    }
+}
+
+/// `derive_master_SK` from EIP-2333: derive the master secret key scalar from a seed.
+fn derive_master_sk(seed: &[u8]) -> [u8; 32] {
+    hkdf_mod_r(seed)
+}
+
+/// `derive_child_SK` from EIP-2333: derive the secret key scalar for `index` given `parent`.
+fn derive_child_sk(parent: &[u8; 32], index: u32) -> [u8; 32] {
+    // `parent_SK_to_lamport_PK`: two 255-chunk Lamport secret keys are expanded from the
+    // parent key and its bit-flipped complement (salted with `index`), each chunk is hashed,
+    // and the concatenation is compressed with SHA256 before `HKDF_mod_r`.
+    let salt = index.to_be_bytes();
+    let not_parent: Vec<u8> = parent.iter().map(|b| !b).collect();
+    let mut compressed_lamport_pk = Sha256::new();
+    for ikm in [&parent[..], &not_parent[..]].iter() {
+        let mut lamport_sk = vec![0u8; 32 * 255];
+        hkdf::Hkdf::<Sha256>::new(Some(&salt[..]), ikm)
+            .expand(&[], &mut lamport_sk)
+            .expect("8160 bytes is a valid HKDF-SHA256 output length");
+        for chunk in lamport_sk.chunks(32) {
+            compressed_lamport_pk.update(Sha256::digest(chunk));
+        }
+    }
+    hkdf_mod_r(&compressed_lamport_pk.finalize())
+}
+
+/// Derive the BLS secret key at EIP-2334 path `m/12381/3600/{index}/{path}`, where `path` is
+/// e.g. `&[0]` for a withdrawal key or `&[0, 0]` for a voting key.
+fn derive_path(seed: &[u8], index: u32, path: &[u32]) -> SecretKey {
+    let mut sk = derive_master_sk(seed);
+    for &child_index in [12381, 3600, index].iter().chain(path.iter()) {
+        sk = derive_child_sk(&sk, child_index);
+    }
+    SecretKey::from_bytes(&sk).expect("a 32-byte HKDF-derived scalar is a valid secret key")
+}
+
+/// Derive the voting and withdrawal keypairs for `validator_index` from `mnemonic`.
+pub fn mnemonic_keypairs(
+    mnemonic: &str,
+    passphrase: &str,
+    validator_index: u32,
+) -> (Keypair, Keypair) {
+    let seed = mnemonic_seed(mnemonic, passphrase);
+
+    let withdrawal_sk = derive_path(&seed, validator_index, &[0]);
+    let withdrawal_pk = PublicKey::from_secret_key(&withdrawal_sk);
+
+    let voting_sk = derive_path(&seed, validator_index, &[0, 0]);
+    let voting_pk = PublicKey::from_secret_key(&voting_sk);
+
+    (
+        Keypair { sk: voting_sk, pk: voting_pk },
+        Keypair { sk: withdrawal_sk, pk: withdrawal_pk },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex;
+
+    const TEST_MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon \
+         abandon abandon abandon abandon about";
+
+    /// The official EIP-2333 `derive_master_SK` test vector: a seed should always derive to
+    /// the same master secret key as every other conformant implementation.
+    #[test]
+    fn eip2333_master_sk_test_vector() {
+        let seed = hex::decode(
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04",
+        )
+        .expect("valid test vector hex");
+
+        let expected_sk = BigUint::parse_bytes(
+            b"6083874454709270928345386274498605044986640685124978867557563392430687146096",
+            10,
+        )
+        .expect("valid test vector decimal");
+
+        let sk = derive_master_sk(&seed);
+        assert_eq!(BigUint::from_bytes_be(&sk), expected_sk);
+    }
+
+    #[test]
+    fn same_mnemonic_is_deterministic() {
+        let (voting_a, withdrawal_a) = mnemonic_keypairs(TEST_MNEMONIC, "", 0);
+        let (voting_b, withdrawal_b) = mnemonic_keypairs(TEST_MNEMONIC, "", 0);
+
+        assert_eq!(voting_a, voting_b);
+        assert_eq!(withdrawal_a, withdrawal_b);
+    }
+
+    #[test]
+    fn different_indices_produce_different_keys() {
+        let (voting_0, _) = mnemonic_keypairs(TEST_MNEMONIC, "", 0);
+        let (voting_1, _) = mnemonic_keypairs(TEST_MNEMONIC, "", 1);
+
+        assert_ne!(voting_0, voting_1);
+    }
+
+    #[test]
+    fn voting_and_withdrawal_keys_differ() {
+        let (voting, withdrawal) = mnemonic_keypairs(TEST_MNEMONIC, "", 0);
+
+        assert_ne!(voting, withdrawal);
+    }
+}
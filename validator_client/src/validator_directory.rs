@@ -1,3 +1,5 @@
+use crate::keystore::Keystore;
+use crate::mnemonic;
 use bls::get_withdrawal_credentials;
 use deposit_contract::encode_eth1_tx_data;
 use hex;
@@ -6,6 +8,8 @@ use slashing_protection::{
     signed_block::SignedBlock,
     validator_history::{SlashingProtection as SlashingProtectionTrait, ValidatorHistory},
 };
+use serde_json;
+use sha2::{Digest, Sha256};
 use ssz::{Decode, Encode};
 use ssz_derive::{Decode, Encode};
 use std::fs;
@@ -14,8 +18,8 @@ use std::io::prelude::*;
 use std::os::unix::fs::PermissionsExt;
 use std::path::PathBuf;
 use types::{
-    test_utils::generate_deterministic_keypair, ChainSpec, DepositData, Hash256, Keypair,
-    PublicKey, SecretKey, Signature,
+    test_utils::generate_deterministic_keypair, ChainSpec, DepositData, Epoch, Hash256, Keypair,
+    PublicKey, SecretKey, Signature, Slot,
 };
 
 const VOTING_KEY_PREFIX: &str = "voting";
@@ -24,16 +28,74 @@ const ETH1_DEPOSIT_DATA_FILE: &str = "eth1_deposit_data.rlp";
 pub const ATTESTER_SLASHING_DB: &str = "attester_slashing_protection.sqlite";
 pub const BLOCK_PRODUCER_SLASHING_DB: &str = "block_producer_slashing_protection.sqlite";
 
+/// The eth2 `DOMAIN_BEACON_PROPOSER` value, mixed into block signing roots.
+pub const DOMAIN_BEACON_PROPOSER: u64 = 0;
+/// The eth2 `DOMAIN_BEACON_ATTESTER` value, mixed into attestation signing roots.
+pub const DOMAIN_BEACON_ATTESTER: u64 = 1;
+
+/// Mixes `domain` into `root`, producing the actual signing root passed to BLS sign/verify.
+///
+/// Without this, a proposer message and an attester message that happen to share an object
+/// root would produce identical signatures; mixing in the domain keeps them separated the way
+/// `compute_signing_root` does in the eth2 spec.
+fn signing_root(root: Hash256, domain: u64) -> Hash256 {
+    let mut hasher = Sha256::new();
+    hasher.update(root.as_bytes());
+    hasher.update(&domain.to_le_bytes());
+    Hash256::from_slice(&hasher.finalize())
+}
+
 /// Returns the filename of a keypair file.
 fn keypair_file(prefix: &str) -> String {
     format!("{}_keypair", prefix)
 }
 
+/// Returns the filename of an encrypted EIP-2335 keystore file.
+fn keystore_file(prefix: &str) -> String {
+    format!("{}_keystore.json", prefix)
+}
+
 /// Returns the name of the folder to be generated for a validator with the given voting key.
 fn dir_name(voting_pubkey: &PublicKey) -> String {
     format!("0x{}", hex::encode(voting_pubkey.as_ssz_bytes()))
 }
 
+const UNIQUE_SUFFIX_LEN: usize = 8;
+const MAX_UNIQUE_DIR_ATTEMPTS: usize = 1000;
+
+/// Returns a short random alphanumeric suffix, used to disambiguate a colliding directory name.
+fn random_suffix() -> String {
+    use rand::Rng;
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(UNIQUE_SUFFIX_LEN)
+        .map(char::from)
+        .collect()
+}
+
+/// Finds a non-existent directory under `base_path` named `name`, or `name` suffixed with a
+/// random string if `name` is already taken. Retries up to `MAX_UNIQUE_DIR_ATTEMPTS` times.
+///
+/// Mirrors `find_unique_filename_using_random_suffix` from ethstore.
+fn find_unique_directory(base_path: &PathBuf, name: &str) -> Result<PathBuf, String> {
+    let directory = base_path.join(name);
+    if !directory.exists() {
+        return Ok(directory);
+    }
+
+    for _ in 0..MAX_UNIQUE_DIR_ATTEMPTS {
+        let candidate = base_path.join(format!("{}-{}", name, random_suffix()));
+        if !candidate.exists() {
+            return Ok(candidate);
+        }
+    }
+
+    Err(format!(
+        "Unable to find a unique directory name for {:?} after {} attempts",
+        directory, MAX_UNIQUE_DIR_ATTEMPTS
+    ))
+}
+
 /// Represents the files/objects for each dedicated lighthouse validator directory.
 ///
 /// Generally lives in `~/.lighthouse/validators/`.
@@ -87,6 +149,136 @@ impl ValidatorDirectory {
             slots_per_epoch: Some(slots_per_epoch),
         })
     }
+
+    /// Identical to `load_for_signing`, except the voting (and, if present, withdrawal)
+    /// keypairs are read from their encrypted EIP-2335 keystores rather than plaintext files.
+    pub fn load_for_signing_with_password(
+        directory: PathBuf,
+        slots_per_epoch: u64,
+        password: &[u8],
+    ) -> Result<Self, String> {
+        if !directory.exists() {
+            return Err(format!(
+                "Validator directory does not exist: {:?}",
+                directory
+            ));
+        }
+
+        let attestation_slashing_protection = directory.join(ATTESTER_SLASHING_DB);
+        let block_slashing_protection = directory.join(BLOCK_PRODUCER_SLASHING_DB);
+
+        if !(attestation_slashing_protection.exists() && block_slashing_protection.exists()) {
+            return Err(format!(
+                "Unable to find slashing protection in {:?}",
+                directory
+            ));
+        }
+        let block_history: ValidatorHistory<SignedBlock> =
+            ValidatorHistory::open(&block_slashing_protection, Some(slots_per_epoch))
+                .map_err(|e| e.to_string())?;
+        let slots_per_epoch = block_history.slots_per_epoch().map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            voting_keypair: Some(
+                load_keystore(directory.clone(), VOTING_KEY_PREFIX, password)
+                    .map_err(|e| format!("Unable to get voting keypair: {}", e))?,
+            ),
+            withdrawal_keypair: load_keystore(directory.clone(), WITHDRAWAL_KEY_PREFIX, password).ok(),
+            deposit_data: load_eth1_deposit_data(directory.clone()).ok(),
+            directory,
+            attestation_slashing_protection: Some(attestation_slashing_protection),
+            block_slashing_protection: Some(block_slashing_protection),
+            slots_per_epoch: Some(slots_per_epoch),
+        })
+    }
+
+    /// The voting public key for this validator, if loaded.
+    pub fn voting_public_key(&self) -> Option<PublicKey> {
+        self.voting_keypair.as_ref().map(|keypair| keypair.pk.clone())
+    }
+
+    /// Sign a block proposal `root` at `slot` with this validator's voting key.
+    ///
+    /// Before signing, `(slot, root)` is checked against (and, if safe, recorded in) this
+    /// validator's block slashing protection history, so a caller can never be tricked into
+    /// proposing two different blocks for the same slot.
+    pub fn sign_block(&self, slot: Slot, root: Hash256) -> Result<Signature, String> {
+        let voting_keypair = self
+            .voting_keypair
+            .as_ref()
+            .ok_or_else(|| "sign_block requires a loaded voting_keypair")?;
+
+        self.check_and_update_block_history(slot, root)?;
+
+        Ok(voting_keypair
+            .sk
+            .sign(signing_root(root, DOMAIN_BEACON_PROPOSER).as_bytes()))
+    }
+
+    /// Sign an attestation `root` with source epoch `source` and target epoch `target`, with
+    /// this validator's voting key.
+    ///
+    /// Before signing, `(source, target, root)` is checked against (and, if safe, recorded in)
+    /// this validator's attestation slashing protection history, so a caller can never be
+    /// tricked into a surround vote or a double vote for the same target epoch.
+    pub fn sign_attestation(
+        &self,
+        source: Epoch,
+        target: Epoch,
+        root: Hash256,
+    ) -> Result<Signature, String> {
+        let voting_keypair = self
+            .voting_keypair
+            .as_ref()
+            .ok_or_else(|| "sign_attestation requires a loaded voting_keypair")?;
+
+        self.check_and_update_attestation_history(source, target, root)?;
+
+        Ok(voting_keypair
+            .sk
+            .sign(signing_root(root, DOMAIN_BEACON_ATTESTER).as_bytes()))
+    }
+
+    /// Verify that `signature` is a valid signature of `root` under `domain` by this
+    /// validator's voting key. `domain` must match whichever of `DOMAIN_BEACON_PROPOSER` /
+    /// `DOMAIN_BEACON_ATTESTER` the signature was produced with.
+    pub fn verify_message(&self, domain: u64, root: Hash256, signature: &Signature) -> bool {
+        match self.voting_public_key() {
+            Some(voting_pubkey) => signature.verify(signing_root(root, domain).as_bytes(), &voting_pubkey),
+            None => false,
+        }
+    }
+
+    fn check_and_update_block_history(&self, slot: Slot, root: Hash256) -> Result<(), String> {
+        let path = self
+            .block_slashing_protection
+            .as_ref()
+            .ok_or_else(|| "sign_block requires block_slashing_protection")?;
+        let mut history: ValidatorHistory<SignedBlock> =
+            ValidatorHistory::open(path, self.slots_per_epoch).map_err(|e| e.to_string())?;
+
+        history
+            .update_if_valid(SignedBlock::new(slot, root))
+            .map_err(|e| format!("Refusing to sign a potentially slashable block: {:?}", e))
+    }
+
+    fn check_and_update_attestation_history(
+        &self,
+        source: Epoch,
+        target: Epoch,
+        root: Hash256,
+    ) -> Result<(), String> {
+        let path = self
+            .attestation_slashing_protection
+            .as_ref()
+            .ok_or_else(|| "sign_attestation requires attestation_slashing_protection")?;
+        let mut history: ValidatorHistory<SignedAttestation> =
+            ValidatorHistory::open(path, self.slots_per_epoch).map_err(|e| e.to_string())?;
+
+        history
+            .update_if_valid(SignedAttestation::new(source, target, root))
+            .map_err(|e| format!("Refusing to sign a potentially slashable attestation: {:?}", e))
+    }
 }
 
 /// Load a `Keypair` from a file.
@@ -109,6 +301,21 @@ fn load_keypair(base_path: PathBuf, file_prefix: &str) -> Result<Keypair, String
         .map_err(|e| format!("Unable to decode keypair: {:?}", e))
 }
 
+/// Load a `Keypair` from an encrypted EIP-2335 keystore file, given the correct `password`.
+fn load_keystore(base_path: PathBuf, file_prefix: &str, password: &[u8]) -> Result<Keypair, String> {
+    let path = base_path.join(keystore_file(file_prefix));
+
+    if !path.exists() {
+        return Err(format!("Keystore file does not exist: {:?}", path));
+    }
+
+    let file = File::open(&path).map_err(|e| format!("Unable to open keystore file: {}", e))?;
+    let keystore: Keystore = serde_json::from_reader(file)
+        .map_err(|e| format!("Unable to parse keystore file: {}", e))?;
+
+    keystore.decrypt(password)
+}
+
 /// Load eth1_deposit_data from file.
 fn load_eth1_deposit_data(base_path: PathBuf) -> Result<Vec<u8>, String> {
     let path = base_path.join(ETH1_DEPOSIT_DATA_FILE);
@@ -202,6 +409,19 @@ impl ValidatorDirectoryBuilder {
         self
     }
 
+    /// Deterministically derive voting and withdrawal keypairs from a BIP-39 `phrase` along the
+    /// EIP-2334 paths for `validator_index`, using EIP-2333 HKDF-based BLS key derivation.
+    ///
+    /// Unlike `thread_random_keypairs`, the same mnemonic always regenerates the exact same
+    /// validator directory, so the phrase alone is sufficient to recover lost key files.
+    pub fn mnemonic_keypairs(mut self, phrase: &str, validator_index: u32) -> Self {
+        let (voting_keypair, withdrawal_keypair) =
+            mnemonic::mnemonic_keypairs(phrase, "", validator_index);
+        self.voting_keypair = Some(voting_keypair);
+        self.withdrawal_keypair = Some(withdrawal_keypair);
+        self
+    }
+
     /// Sets the slots_per_epoch
     pub fn slots_per_epoch(mut self, slots_per_epoch: u64) -> Self {
         self.slots_per_epoch = Some(slots_per_epoch);
@@ -244,7 +464,77 @@ impl ValidatorDirectoryBuilder {
         Ok(self)
     }
 
-    /// Write the validators keypairs to disk.
+    /// Like `create_directory`, but if `dir_name(voting_pubkey)` already exists under
+    /// `base_path`, appends a short random suffix to produce a unique sibling directory instead
+    /// of failing. Useful for tooling that generates or re-imports many directories and would
+    /// otherwise need to handle the collision itself.
+    pub fn create_directory_deduplicated(mut self, base_path: PathBuf) -> Result<Self, String> {
+        let voting_keypair = self
+            .voting_keypair
+            .as_ref()
+            .ok_or_else(|| "directory requires a voting_keypair")?;
+
+        let directory = find_unique_directory(&base_path, &dir_name(&voting_keypair.pk))?;
+
+        fs::create_dir_all(&directory)
+            .map_err(|e| format!("Unable to create validator directory: {}", e))?;
+
+        self.directory = Some(directory);
+
+        Ok(self)
+    }
+
+    /// Write the validators keypairs to disk as encrypted EIP-2335 keystores, protected by
+    /// `password`.
+    pub fn write_encrypted_keypair_files(self, password: &[u8]) -> Result<Self, String> {
+        let voting_keypair = self
+            .voting_keypair
+            .clone()
+            .ok_or_else(|| "write_encrypted_keypair_files requires a voting_keypair")?;
+        let withdrawal_keypair = self
+            .withdrawal_keypair
+            .clone()
+            .ok_or_else(|| "write_encrypted_keypair_files requires a withdrawal_keypair")?;
+
+        self.save_keystore(voting_keypair, VOTING_KEY_PREFIX, password)?;
+        self.save_keystore(withdrawal_keypair, WITHDRAWAL_KEY_PREFIX, password)?;
+        Ok(self)
+    }
+
+    fn save_keystore(&self, keypair: Keypair, file_prefix: &str, password: &[u8]) -> Result<(), String> {
+        let path = self
+            .directory
+            .as_ref()
+            .map(|directory| directory.join(keystore_file(file_prefix)))
+            .ok_or_else(|| "save_keystore requires a directory")?;
+
+        if path.exists() {
+            return Err(format!("Keystore file already exists at: {:?}", path));
+        }
+
+        let keystore = Keystore::encrypt(&keypair, password, None)?;
+
+        let mut file = File::create(&path).map_err(|e| format!("Unable to create file: {}", e))?;
+
+        let mut perm = file
+            .metadata()
+            .map_err(|e| format!("Unable to get file metadata: {}", e))?
+            .permissions();
+        perm.set_mode((libc::S_IWUSR | libc::S_IRUSR) as u32);
+        file.set_permissions(perm)
+            .map_err(|e| format!("Unable to set file permissions: {}", e))?;
+
+        serde_json::to_writer(&mut file, &keystore)
+            .map_err(|e| format!("Unable to write keystore to file: {}", e))?;
+
+        Ok(())
+    }
+
+    /// Write the validators keypairs to disk as raw, unencrypted SSZ bytes.
+    ///
+    /// ## Warning
+    /// Only the Unix file permissions protect these keys at rest. Prefer
+    /// `write_encrypted_keypair_files` outside of tests.
     pub fn write_keypair_files(self) -> Result<Self, String> {
         let voting_keypair = self
             .voting_keypair
@@ -391,6 +681,83 @@ mod tests {
 
     type E = MinimalEthSpec;
 
+    fn build_validator_dir(temp_dir: &TempDir) -> ValidatorDirectory {
+        let spec = E::default_spec();
+
+        ValidatorDirectoryBuilder::default()
+            .spec(spec)
+            .slots_per_epoch(E::slots_per_epoch())
+            .full_deposit_amount()
+            .expect("should set full deposit amount")
+            .thread_random_keypairs()
+            .create_directory(temp_dir.path().into())
+            .expect("should create directory")
+            .write_keypair_files()
+            .expect("should write keypair files")
+            .write_eth1_data_file()
+            .expect("should write eth1 data file")
+            .create_sqlite_slashing_dbs()
+            .expect("should create slashing dbs")
+            .build()
+            .expect("should build dir")
+    }
+
+    #[test]
+    fn sign_and_verify_block() {
+        let temp_dir = TempDir::new("acc_manager").expect("should create test dir");
+        let validator_dir = build_validator_dir(&temp_dir);
+
+        let root = Hash256::from_low_u64_be(42);
+        let signature = validator_dir
+            .sign_block(Slot::new(0), root)
+            .expect("should sign a fresh block");
+
+        assert!(validator_dir.verify_message(DOMAIN_BEACON_PROPOSER, root, &signature));
+        assert!(!validator_dir.verify_message(DOMAIN_BEACON_ATTESTER, root, &signature));
+
+        assert!(
+            validator_dir
+                .sign_block(Slot::new(0), Hash256::from_low_u64_be(43))
+                .is_err(),
+            "signing a conflicting block at the same slot should be refused"
+        );
+
+        assert!(
+            validator_dir
+                .sign_block(Slot::new(1), Hash256::from_low_u64_be(43))
+                .is_ok(),
+            "signing a different block at a later slot should be allowed"
+        );
+    }
+
+    #[test]
+    fn sign_and_verify_attestation() {
+        let temp_dir = TempDir::new("acc_manager").expect("should create test dir");
+        let validator_dir = build_validator_dir(&temp_dir);
+
+        let root = Hash256::from_low_u64_be(7);
+        let signature = validator_dir
+            .sign_attestation(Epoch::new(0), Epoch::new(1), root)
+            .expect("should sign a fresh attestation");
+
+        assert!(validator_dir.verify_message(DOMAIN_BEACON_ATTESTER, root, &signature));
+        assert!(!validator_dir.verify_message(DOMAIN_BEACON_PROPOSER, root, &signature));
+
+        assert!(
+            validator_dir
+                .sign_attestation(Epoch::new(0), Epoch::new(1), Hash256::from_low_u64_be(8))
+                .is_err(),
+            "signing a conflicting attestation for the same source/target should be refused"
+        );
+
+        assert!(
+            validator_dir
+                .sign_attestation(Epoch::new(1), Epoch::new(2), Hash256::from_low_u64_be(8))
+                .is_ok(),
+            "signing a different attestation for a later target epoch should be allowed"
+        );
+    }
+
     #[test]
     fn random_keypairs_round_trip() {
         let spec = E::default_spec();
@@ -425,6 +792,115 @@ mod tests {
         );
     }
 
+    #[test]
+    fn encrypted_keypairs_round_trip() {
+        let spec = E::default_spec();
+        let temp_dir = TempDir::new("acc_manager").expect("should create test dir");
+        let password = b"an excellent password";
+
+        let created_dir = ValidatorDirectoryBuilder::default()
+            .spec(spec)
+            .slots_per_epoch(E::slots_per_epoch())
+            .full_deposit_amount()
+            .expect("should set full deposit amount")
+            .thread_random_keypairs()
+            .create_directory(temp_dir.path().into())
+            .expect("should create directory")
+            .write_encrypted_keypair_files(password)
+            .expect("should write encrypted keypair files")
+            .write_eth1_data_file()
+            .expect("should write eth1 data file")
+            .create_sqlite_slashing_dbs()
+            .expect("should create slashing dbs")
+            .build()
+            .expect("should build dir");
+
+        let loaded_dir = ValidatorDirectory::load_for_signing_with_password(
+            created_dir.directory.clone(),
+            E::slots_per_epoch(),
+            password,
+        )
+        .expect("should load directory with correct password");
+
+        assert_eq!(
+            created_dir, loaded_dir,
+            "the directory created should match the one loaded"
+        );
+
+        assert!(
+            ValidatorDirectory::load_for_signing_with_password(
+                created_dir.directory.clone(),
+                E::slots_per_epoch(),
+                b"wrong password",
+            )
+            .is_err(),
+            "loading with the wrong password should fail"
+        );
+    }
+
+    #[test]
+    fn create_directory_deduplicated_avoids_collisions() {
+        let temp_dir = TempDir::new("acc_manager").expect("should create test dir");
+        let index = 99;
+
+        let first = ValidatorDirectoryBuilder::default()
+            .insecure_keypairs(index)
+            .create_directory_deduplicated(temp_dir.path().into())
+            .expect("should create first directory")
+            .directory
+            .expect("should have a directory");
+
+        let second = ValidatorDirectoryBuilder::default()
+            .insecure_keypairs(index)
+            .create_directory_deduplicated(temp_dir.path().into())
+            .expect("should create second directory despite the collision")
+            .directory
+            .expect("should have a directory");
+
+        assert_ne!(first, second, "colliding directories should land in distinct paths");
+        assert!(first.exists());
+        assert!(second.exists());
+    }
+
+    #[test]
+    fn mnemonic_keypairs_round_trip() {
+        let spec = E::default_spec();
+        let temp_dir = TempDir::new("acc_manager").expect("should create test dir");
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon \
+            abandon abandon abandon about";
+
+        let created_dir = ValidatorDirectoryBuilder::default()
+            .spec(spec)
+            .slots_per_epoch(E::slots_per_epoch())
+            .full_deposit_amount()
+            .expect("should set full deposit amount")
+            .mnemonic_keypairs(phrase, 0)
+            .create_directory(temp_dir.path().into())
+            .expect("should create directory")
+            .write_keypair_files()
+            .expect("should write keypair files")
+            .write_eth1_data_file()
+            .expect("should write eth1 data file")
+            .create_sqlite_slashing_dbs()
+            .expect("should create slashing dbs")
+            .build()
+            .expect("should build dir");
+
+        let (regenerated_voting, regenerated_withdrawal) =
+            mnemonic::mnemonic_keypairs(phrase, "", 0);
+
+        assert_eq!(
+            created_dir.voting_keypair,
+            Some(regenerated_voting),
+            "the same mnemonic should always regenerate the same voting keypair"
+        );
+        assert_eq!(
+            created_dir.withdrawal_keypair,
+            Some(regenerated_withdrawal),
+            "the same mnemonic should always regenerate the same withdrawal keypair"
+        );
+    }
+
     #[test]
     fn deterministic_keypairs_round_trip() {
         let spec = E::default_spec();
@@ -0,0 +1,199 @@
+//! A `Vault` groups many validator directories under a single master password, modeled on
+//! OpenEthereum's vaults subsystem: unlock once with the vault password and gain signing access
+//! to every validator keystore stored inside, rather than remembering one password per key.
+use crate::keystore::{decrypt_raw, encrypt_raw, Crypto};
+use crate::validator_directory::{ValidatorDirectory, ValidatorDirectoryBuilder};
+use hex;
+use rand;
+use serde::{Deserialize, Serialize};
+use serde_json;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+const VAULT_METADATA_FILE: &str = "vault.json";
+const VAULT_ID_FILE: &str = "vault_id";
+
+/// The `vault.json` metadata file stored at the root of a vault directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct VaultMetadata {
+    /// Identifies which vault a validator subdirectory was encrypted under, so `iter_validators`
+    /// can refuse to load validators that don't belong to this vault before even trying a password.
+    vault_id: String,
+    /// `sha256(password)`, encrypted with a key derived from that same password. Opening the
+    /// vault re-derives the key from the supplied password and checks the decrypted value
+    /// matches, without ever storing the password (or an unsalted hash of it) in the clear.
+    password_check: Crypto,
+}
+
+fn sha256(bytes: &[u8]) -> Vec<u8> {
+    Sha256::digest(bytes).to_vec()
+}
+
+fn metadata_path(vault_path: &PathBuf) -> PathBuf {
+    vault_path.join(VAULT_METADATA_FILE)
+}
+
+fn read_metadata(vault_path: &PathBuf) -> Result<VaultMetadata, String> {
+    let file = File::open(metadata_path(vault_path))
+        .map_err(|e| format!("Unable to open vault metadata: {}", e))?;
+    serde_json::from_reader(file).map_err(|e| format!("Unable to parse vault metadata: {}", e))
+}
+
+fn write_metadata(vault_path: &PathBuf, metadata: &VaultMetadata) -> Result<(), String> {
+    let mut file = File::create(metadata_path(vault_path))
+        .map_err(|e| format!("Unable to create vault metadata file: {}", e))?;
+    serde_json::to_writer(&mut file, metadata)
+        .map_err(|e| format!("Unable to write vault metadata: {}", e))
+}
+
+/// A password-protected collection of `ValidatorDirectory`s, all encrypted under one master
+/// password so an operator only has to unlock once to gain signing access to every validator.
+pub struct Vault {
+    directory: PathBuf,
+    password: Vec<u8>,
+    vault_id: String,
+}
+
+impl Vault {
+    /// Create a new, empty vault at `path`, protected by `password`.
+    pub fn create(path: PathBuf, password: &[u8]) -> Result<Self, String> {
+        if path.exists() {
+            return Err(format!("Vault directory already exists: {:?}", path));
+        }
+
+        fs::create_dir_all(&path).map_err(|e| format!("Unable to create vault directory: {}", e))?;
+
+        let vault_id = hex::encode(rand::random::<[u8; 16]>());
+        let password_check = encrypt_raw(&sha256(password), password, None)?;
+
+        write_metadata(&path, &VaultMetadata { vault_id: vault_id.clone(), password_check })?;
+
+        Ok(Self { directory: path, password: password.to_vec(), vault_id })
+    }
+
+    /// Open an existing vault at `path`, verifying `password` against the stored check before
+    /// granting access.
+    pub fn open(path: PathBuf, password: &[u8]) -> Result<Self, String> {
+        let metadata = read_metadata(&path)?;
+
+        let decrypted = decrypt_raw(&metadata.password_check, password)
+            .map_err(|_| "Incorrect vault password".to_string())?;
+        if decrypted != sha256(password) {
+            return Err("Incorrect vault password".to_string());
+        }
+
+        Ok(Self {
+            directory: path,
+            password: password.to_vec(),
+            vault_id: metadata.vault_id,
+        })
+    }
+
+    /// Build and add a new validator directory inside this vault, encrypted with the vault's
+    /// password rather than a per-validator one.
+    ///
+    /// `create_sqlite_slashing_dbs` is still run per validator, so slashing protection history
+    /// remains independent of the vault's single shared password.
+    pub fn add_validator(&self, builder: ValidatorDirectoryBuilder) -> Result<ValidatorDirectory, String> {
+        let validator_directory = builder
+            .create_directory(self.directory.clone())?
+            .write_encrypted_keypair_files(&self.password)?
+            .create_sqlite_slashing_dbs()?
+            .build()?;
+
+        let mut vault_id_file = File::create(validator_directory.directory.join(VAULT_ID_FILE))
+            .map_err(|e| format!("Unable to create vault id file: {}", e))?;
+        vault_id_file
+            .write_all(self.vault_id.as_bytes())
+            .map_err(|e| format!("Unable to write vault id file: {}", e))?;
+
+        Ok(validator_directory)
+    }
+
+    /// Load every validator directory stored in this vault, ready for signing.
+    ///
+    /// Validator subdirectories whose `vault_id` doesn't match this vault are skipped entirely,
+    /// without ever attempting to decrypt their keystores.
+    pub fn iter_validators(&self, slots_per_epoch: u64) -> Result<Vec<ValidatorDirectory>, String> {
+        let mut validators = vec![];
+
+        let entries = fs::read_dir(&self.directory)
+            .map_err(|e| format!("Unable to read vault directory: {}", e))?;
+
+        for entry in entries {
+            let entry = entry.map_err(|e| format!("Unable to read vault entry: {}", e))?;
+            let path = entry.path();
+
+            if !path.is_dir() {
+                continue;
+            }
+
+            let vault_id_path = path.join(VAULT_ID_FILE);
+            if !vault_id_path.exists() {
+                continue;
+            }
+
+            let mut vault_id = String::new();
+            File::open(&vault_id_path)
+                .map_err(|e| format!("Unable to open vault id file: {}", e))?
+                .read_to_string(&mut vault_id)
+                .map_err(|e| format!("Unable to read vault id file: {}", e))?;
+
+            if vault_id != self.vault_id {
+                continue;
+            }
+
+            validators.push(ValidatorDirectory::load_for_signing_with_password(
+                path,
+                slots_per_epoch,
+                &self.password,
+            )?);
+        }
+
+        Ok(validators)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+    use types::{test_utils::generate_deterministic_keypair, EthSpec, MinimalEthSpec};
+
+    type E = MinimalEthSpec;
+
+    #[test]
+    fn vault_rejects_wrong_password() {
+        let temp_dir = TempDir::new("vault").expect("should create test dir");
+        let vault_path = temp_dir.path().join("my_vault");
+
+        Vault::create(vault_path.clone(), b"vault password").expect("should create vault");
+
+        assert!(Vault::open(vault_path, b"wrong password").is_err());
+    }
+
+    #[test]
+    fn vault_add_and_iter_validators() {
+        let temp_dir = TempDir::new("vault").expect("should create test dir");
+        let vault_path = temp_dir.path().join("my_vault");
+
+        let vault = Vault::create(vault_path, b"vault password").expect("should create vault");
+
+        let builder = ValidatorDirectoryBuilder::default()
+            .slots_per_epoch(E::slots_per_epoch())
+            .insecure_keypairs(7);
+        vault.add_validator(builder).expect("should add validator");
+
+        let validators = vault
+            .iter_validators(E::slots_per_epoch())
+            .expect("should iterate validators");
+
+        assert_eq!(validators.len(), 1);
+        assert_eq!(
+            validators[0].voting_keypair,
+            Some(generate_deterministic_keypair(7))
+        );
+    }
+}